@@ -0,0 +1,102 @@
+//! Applies cgroup v2 resource limits (memory, CPU, PID count) to a spawned process.
+//! Writes directly to the unified hierarchy's `memory.max`, `pids.max`, and `cpu.max`
+//! controller files rather than depending on a cgroup management crate.
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The standard mount point for the cgroup v2 unified hierarchy.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The `cpu.max` period (in microseconds) used when translating `--cpu-limit` into a quota.
+const CPU_PERIOD_MICROS: u64 = 100_000;
+
+/// Resource limits to apply to a spawned venv's Python process, as configured on the CLI.
+#[derive(Default)]
+pub struct ResourceLimits {
+    /// Maximum resident memory, in bytes.
+    pub memory_limit: Option<u64>,
+    /// Maximum CPU usage, in fractional cores (e.g. `0.5` for half a CPU).
+    pub cpu_limit: Option<f64>,
+    /// Maximum number of PIDs the process (and its children) may create.
+    pub pids_limit: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.memory_limit.is_none() && self.cpu_limit.is_none() && self.pids_limit.is_none()
+    }
+}
+
+/// A cgroup v2 group created to hold a single spawned process. Removing the group directory
+/// (which also requires the group to be empty) is attempted on drop.
+pub struct CgroupGuard {
+    path: PathBuf,
+}
+
+impl Drop for CgroupGuard {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir(&self.path) {
+            log::warn!("Failed to remove cgroup at {:?}: {:?}", self.path, err);
+        }
+    }
+}
+
+/// Creates a fresh cgroup v2 group named `name` under the unified hierarchy and applies
+/// `limits` to it. Returns `Ok(None)` if `limits` is empty, or if cgroup v2 delegation isn't
+/// available, so non-Linux or unprivileged environments still work, just without enforcement.
+pub fn create(name: &str, limits: &ResourceLimits) -> anyhow::Result<Option<CgroupGuard>> {
+    if limits.is_empty() {
+        return Ok(None);
+    }
+
+    if !is_available() {
+        log::warn!(
+            "cgroup v2 delegation is not available at {}; ignoring resource limits",
+            CGROUP_ROOT
+        );
+        return Ok(None);
+    }
+
+    let path = Path::new(CGROUP_ROOT).join(name);
+    std::fs::create_dir(&path)?;
+    let guard = CgroupGuard { path };
+
+    if let Some(memory_limit) = limits.memory_limit {
+        write_control(&guard.path, "memory.max", memory_limit.to_string())?;
+    }
+    if let Some(pids_limit) = limits.pids_limit {
+        write_control(&guard.path, "pids.max", pids_limit.to_string())?;
+    }
+    if let Some(cpu_limit) = limits.cpu_limit {
+        let quota = (cpu_limit * CPU_PERIOD_MICROS as f64).round() as u64;
+        write_control(
+            &guard.path,
+            "cpu.max",
+            format!("{quota} {CPU_PERIOD_MICROS}"),
+        )?;
+    }
+
+    Ok(Some(guard))
+}
+
+impl CgroupGuard {
+    /// The path of this cgroup's `cgroup.procs` file, which a process joins by writing its own
+    /// pid into it. Exposed so a caller can join a child to the cgroup from a `pre_exec` closure
+    /// running inside the forked child, before it execs, rather than racing a write from the
+    /// parent after `spawn` returns.
+    pub fn procs_path(&self) -> PathBuf {
+        self.path.join("cgroup.procs")
+    }
+}
+
+fn write_control(cgroup_path: &Path, file: &str, contents: String) -> anyhow::Result<()> {
+    std::fs::write(cgroup_path.join(file), contents)?;
+    Ok(())
+}
+
+/// Checks whether the cgroup v2 unified hierarchy is mounted and delegated to us, i.e. we're
+/// able to create subgroups under it.
+fn is_available() -> bool {
+    let root = Path::new(CGROUP_ROOT);
+    root.join("cgroup.controllers").exists() && root.join("cgroup.subtree_control").exists()
+}
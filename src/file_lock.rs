@@ -3,11 +3,31 @@
 //! except that it supports atomically upgrading / downgrading the lock.
 use libc::fcntl;
 use libc::flock;
+use libc::F_SETLK;
 use libc::F_SETLKW;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The initial delay between `try_*` polls when waiting on a lock with a timeout.
+/// This doubles on each retry, up to [`MAX_BACKOFF`].
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Errors that can occur while acquiring a [`FileLock`].
+#[derive(Debug, thiserror::Error)]
+pub enum LockError {
+    /// The lock is currently held by another process and non-blocking acquisition was requested.
+    #[error("lock is held by another process")]
+    WouldBlock,
+
+    /// Some other I/O failure occurred while attempting to acquire the lock.
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
 
 pub struct FileLock {
     file: File,
@@ -30,6 +50,30 @@ impl FileLock {
     pub fn write(&mut self) -> anyhow::Result<WriteLock> {
         WriteLock::new(&mut self.file)
     }
+
+    /// Like [`FileLock::read`], but returns [`LockError::WouldBlock`] immediately instead of
+    /// waiting if the lock is already held by another process.
+    pub fn try_read(&mut self) -> Result<ReadLock, LockError> {
+        ReadLock::try_new(&mut self.file)
+    }
+
+    /// Like [`FileLock::write`], but returns [`LockError::WouldBlock`] immediately instead of
+    /// waiting if the lock is already held by another process.
+    pub fn try_write(&mut self) -> Result<WriteLock, LockError> {
+        WriteLock::try_new(&mut self.file)
+    }
+
+    /// Like [`FileLock::read`], but gives up and returns [`LockError::WouldBlock`] if the lock
+    /// cannot be acquired before `timeout` elapses.
+    pub fn read_timeout(&mut self, timeout: Duration) -> Result<ReadLock, LockError> {
+        ReadLock::new_timeout(&mut self.file, timeout)
+    }
+
+    /// Like [`FileLock::write`], but gives up and returns [`LockError::WouldBlock`] if the lock
+    /// cannot be acquired before `timeout` elapses.
+    pub fn write_timeout(&mut self, timeout: Duration) -> Result<WriteLock, LockError> {
+        WriteLock::new_timeout(&mut self.file, timeout)
+    }
 }
 
 pub struct ReadLock<'a> {
@@ -39,7 +83,19 @@ pub struct ReadLock<'a> {
 impl<'a> ReadLock<'a> {
     fn new(file: &'a mut File) -> anyhow::Result<Self> {
         log::debug!("Taking read lock on file {:?}", file);
-        apply_lock(file, LockOperation::Read)?;
+        apply_lock(file, LockOperation::Read, Blocking::Blocking)?;
+        Ok(Self { file: Some(file) })
+    }
+
+    fn try_new(file: &'a mut File) -> Result<Self, LockError> {
+        log::debug!("Attempting to take read lock on file {:?}", file);
+        apply_lock(file, LockOperation::Read, Blocking::NonBlocking)?;
+        Ok(Self { file: Some(file) })
+    }
+
+    fn new_timeout(file: &'a mut File, timeout: Duration) -> Result<Self, LockError> {
+        log::debug!("Taking read lock on file {:?} with timeout {:?}", file, timeout);
+        retry_with_backoff(timeout, || apply_lock(file, LockOperation::Read, Blocking::NonBlocking))?;
         Ok(Self { file: Some(file) })
     }
 
@@ -53,7 +109,7 @@ impl<'a> ReadLock<'a> {
 impl Drop for ReadLock<'_> {
     fn drop(&mut self) {
         if let Some(ref mut file) = self.file {
-            apply_lock(file, LockOperation::Unlock)
+            apply_lock(file, LockOperation::Unlock, Blocking::Blocking)
                 .expect("Failed to unlock file during ReadLock Drop");
         }
     }
@@ -66,7 +122,19 @@ pub struct WriteLock<'a> {
 impl<'a> WriteLock<'a> {
     fn new(file: &'a mut File) -> anyhow::Result<Self> {
         log::debug!("Taking write lock on file {:?}", file);
-        apply_lock(file, LockOperation::Write)?;
+        apply_lock(file, LockOperation::Write, Blocking::Blocking)?;
+        Ok(Self { file: Some(file) })
+    }
+
+    fn try_new(file: &'a mut File) -> Result<Self, LockError> {
+        log::debug!("Attempting to take write lock on file {:?}", file);
+        apply_lock(file, LockOperation::Write, Blocking::NonBlocking)?;
+        Ok(Self { file: Some(file) })
+    }
+
+    fn new_timeout(file: &'a mut File, timeout: Duration) -> Result<Self, LockError> {
+        log::debug!("Taking write lock on file {:?} with timeout {:?}", file, timeout);
+        retry_with_backoff(timeout, || apply_lock(file, LockOperation::Write, Blocking::NonBlocking))?;
         Ok(Self { file: Some(file) })
     }
 
@@ -80,7 +148,7 @@ impl<'a> WriteLock<'a> {
 impl Drop for WriteLock<'_> {
     fn drop(&mut self) {
         if let Some(ref mut file) = self.file {
-            apply_lock(file, LockOperation::Unlock)
+            apply_lock(file, LockOperation::Unlock, Blocking::Blocking)
                 .expect("Failed to unlock file during WriteLock Drop");
         }
     }
@@ -92,17 +160,54 @@ enum LockOperation {
     Unlock,
 }
 
-fn apply_lock(file: &mut File, operation: LockOperation) -> anyhow::Result<()> {
+#[derive(Clone, Copy)]
+enum Blocking {
+    Blocking,
+    NonBlocking,
+}
+
+/// Retries `acquire` with exponential backoff until it succeeds or `timeout` elapses, at which
+/// point the last [`LockError::WouldBlock`] is returned.
+fn retry_with_backoff(
+    timeout: Duration,
+    mut acquire: impl FnMut() -> Result<(), LockError>,
+) -> Result<(), LockError> {
+    let deadline = Instant::now() + timeout;
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        match acquire() {
+            Ok(()) => return Ok(()),
+            Err(LockError::WouldBlock) if Instant::now() >= deadline => {
+                return Err(LockError::WouldBlock)
+            }
+            Err(LockError::WouldBlock) => {
+                std::thread::sleep(backoff.min(deadline.saturating_duration_since(Instant::now())));
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn apply_lock(
+    file: &mut File,
+    operation: LockOperation,
+    blocking: Blocking,
+) -> Result<(), LockError> {
     let fd = file.as_raw_fd();
     let lock_type: libc::c_short = match operation {
         LockOperation::Read => libc::F_RDLCK,
         LockOperation::Write => libc::F_WRLCK,
         LockOperation::Unlock => libc::F_UNLCK,
     } as libc::c_short;
+    let cmd = match blocking {
+        Blocking::Blocking => F_SETLKW,
+        Blocking::NonBlocking => F_SETLK,
+    };
     let result = unsafe {
         fcntl(
             fd,
-            F_SETLKW,
+            cmd,
             &flock {
                 l_type: lock_type,
                 l_whence: libc::SEEK_SET as i16,
@@ -114,7 +219,101 @@ fn apply_lock(file: &mut File, operation: LockOperation) -> anyhow::Result<()> {
     };
     if result == -1 {
         let err = std::io::Error::last_os_error();
-        return Err(err.into())
+        return match err.raw_os_error() {
+            Some(libc::EACCES) | Some(libc::EAGAIN) => Err(LockError::WouldBlock),
+            _ => Err(LockError::Other(err.into())),
+        };
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_try_write_succeeds_when_uncontended() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("venvcache-file-lock-test")?;
+        let path = tempdir.path().join("lock");
+        let mut lock = FileLock::new(&path)?;
+        let _write_lock = lock.try_write()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_read_and_try_write_would_block_on_another_process() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("venvcache-file-lock-test")?;
+        let path = tempdir.path().join("lock");
+        // Open (and thus create) the file before forking, so both processes lock the same
+        // inode rather than racing to create it.
+        FileLock::new(&path)?;
+
+        with_lock_held_by_child(&path, Duration::from_millis(300), || {
+            let mut lock = FileLock::new(&path)?;
+            assert!(matches!(lock.try_write(), Err(LockError::WouldBlock)));
+            assert!(matches!(lock.try_read(), Err(LockError::WouldBlock)));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_write_timeout_expires_while_contended() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("venvcache-file-lock-test")?;
+        let path = tempdir.path().join("lock");
+        FileLock::new(&path)?;
+
+        with_lock_held_by_child(&path, Duration::from_millis(500), || {
+            let mut lock = FileLock::new(&path)?;
+            let result = lock.write_timeout(Duration::from_millis(50));
+            assert!(matches!(result, Err(LockError::WouldBlock)));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_write_timeout_succeeds_once_released() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("venvcache-file-lock-test")?;
+        let path = tempdir.path().join("lock");
+        FileLock::new(&path)?;
+
+        with_lock_held_by_child(&path, Duration::from_millis(100), || {
+            let mut lock = FileLock::new(&path)?;
+            let result = lock.write_timeout(Duration::from_secs(2));
+            assert!(result.is_ok());
+            Ok(())
+        })
+    }
+
+    /// Forks a child process that takes a write lock on `path`, holds it for `hold_for`, then
+    /// exits, and runs `in_parent` while the child still holds the lock. `fcntl` locks are
+    /// scoped to the owning process, so exercising real contention requires a second process
+    /// rather than a second thread or file descriptor in this same one.
+    fn with_lock_held_by_child(
+        path: &Path,
+        hold_for: Duration,
+        in_parent: impl FnOnce() -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let pid = unsafe { libc::fork() };
+        anyhow::ensure!(pid >= 0, "fork failed: {:?}", std::io::Error::last_os_error());
+
+        if pid == 0 {
+            let result = (|| -> anyhow::Result<()> {
+                let mut lock = FileLock::new(path)?;
+                let _write_lock = lock.write()?;
+                std::thread::sleep(hold_for);
+                Ok(())
+            })();
+            std::process::exit(if result.is_ok() { 0 } else { 1 });
+        }
+
+        // Give the child a moment to grab the lock before we start contending for it.
+        std::thread::sleep(Duration::from_millis(100));
+        let result = in_parent();
+
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+
+        result
+    }
+}
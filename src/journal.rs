@@ -1,18 +1,33 @@
 use std::path::Path;
 
+/// The default half-life used to decay a resource's frecency score when none is configured.
+const DEFAULT_HALF_LIFE_SECS: f64 = 7.0 * 24.0 * 60.0 * 60.0;
+
 /// Provides an interface to a least frecency-used cache.
 /// Allows one to journal the usage of resources (identified by fingerprints)
 /// on disk, and calculate which resource is most optimal to delete.
 pub struct Journal {
     db: rusqlite::Connection,
     maximum_resources: usize,
+    half_life_secs: f64,
 }
 
 impl Journal {
     pub fn new(path: impl AsRef<Path>, maximum_resources: usize) -> anyhow::Result<Self> {
+        Self::with_half_life(path, maximum_resources, DEFAULT_HALF_LIFE_SECS)
+    }
+
+    /// Like [`Journal::new`], but allows configuring the half-life (in seconds) used when
+    /// decaying a resource's frecency score as it ages.
+    pub fn with_half_life(
+        path: impl AsRef<Path>,
+        maximum_resources: usize,
+        half_life_secs: f64,
+    ) -> anyhow::Result<Self> {
         let mut db = Self {
             db: rusqlite::Connection::open(path)?,
             maximum_resources,
+            half_life_secs,
         };
         db.migrate()?;
         Ok(db)
@@ -23,11 +38,24 @@ impl Journal {
             r#"
             CREATE TABLE IF NOT EXISTS resources (
                 fingerprint VARCHAR PRIMARY KEY,
-                last_used DATETIME NOT NULL
+                last_used DATETIME NOT NULL,
+                use_count INTEGER NOT NULL DEFAULT 1
             )
             "#,
             (),
         )?;
+
+        let has_use_count = self
+            .db
+            .prepare("SELECT 1 FROM pragma_table_info('resources') WHERE name = 'use_count'")?
+            .exists(())?;
+        if !has_use_count {
+            self.db.execute(
+                "ALTER TABLE resources ADD COLUMN use_count INTEGER NOT NULL DEFAULT 1",
+                (),
+            )?;
+        }
+
         Ok(())
     }
 
@@ -42,38 +70,57 @@ impl Journal {
             r#"
             INSERT INTO resources(
                 fingerprint,
-                last_used
+                last_used,
+                use_count
             ) VALUES (
                 ?,
-                ?
+                ?,
+                1
             ) ON CONFLICT(fingerprint)
-            DO UPDATE SET last_used=?
+            DO UPDATE SET last_used=?, use_count=use_count + 1
             "#,
             (fingerprint, now, now),
         )?;
 
-        let mut stmt = self.db.prepare(
-            r#"
-            SELECT *
-            FROM (
-                SELECT
-                    fingerprint,
-                    last_used,
-                    ROW_NUMBER() OVER (ORDER BY last_used DESC) AS row_num
-                FROM resources
-            )
-            WHERE row_num > ?
-            ORDER BY last_used ASC
-            "#,
-        )?;
-        let expired_resources: Vec<String> = stmt
-            .query_map((self.maximum_resources,), |row| row.get(0))?
+        let mut stmt = self
+            .db
+            .prepare("SELECT fingerprint, last_used, use_count FROM resources")?;
+        let mut resources: Vec<(String, chrono::DateTime<chrono::Utc>, i64)> = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
             .flatten()
             .collect();
 
+        let lambda = 2f64.ln() / self.half_life_secs;
+        resources.sort_by(|(_, a_last_used, a_use_count), (_, b_last_used, b_use_count)| {
+            let a_frecency = frecency(*a_use_count, *a_last_used, now, lambda);
+            let b_frecency = frecency(*b_use_count, *b_last_used, now, lambda);
+            a_frecency
+                .partial_cmp(&b_frecency)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a_last_used.cmp(b_last_used))
+        });
+
+        let expired_resources: Vec<String> = resources
+            .into_iter()
+            .rev()
+            .skip(self.maximum_resources)
+            .rev()
+            .map(|(fingerprint, _, _)| fingerprint)
+            .collect();
+
         Ok(expired_resources)
     }
 
+    /// Returns every currently-journaled fingerprint along with its `last_used` timestamp.
+    pub fn list_resources(&self) -> anyhow::Result<Vec<(String, chrono::DateTime<chrono::Utc>)>> {
+        let mut stmt = self.db.prepare("SELECT fingerprint, last_used FROM resources")?;
+        let resources = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .flatten()
+            .collect();
+        Ok(resources)
+    }
+
     /// Marks a particular resource as deleted.
     pub fn mark_deleted(&self, fingerprint: &str) -> anyhow::Result<()> {
         log::debug!("Marking fingerprint as deleted: `{}`", fingerprint);
@@ -85,6 +132,18 @@ impl Journal {
     }
 }
 
+/// Computes a resource's frecency score: a frequency-weighted recency score that decays
+/// exponentially with `lambda` as the resource ages.
+fn frecency(
+    use_count: i64,
+    last_used: chrono::DateTime<chrono::Utc>,
+    now: chrono::DateTime<chrono::Utc>,
+    lambda: f64,
+) -> f64 {
+    let age_seconds = (now - last_used).num_seconds().max(0) as f64;
+    use_count as f64 * (-lambda * age_seconds).exp()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -137,4 +196,73 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_journal_list_resources() -> anyhow::Result<()> {
+        let (_tempdir, journal) = test_journal(10)?;
+        journal.record_usage("fingerprint1")?;
+        journal.record_usage("fingerprint2")?;
+
+        let mut fingerprints: Vec<String> = journal
+            .list_resources()?
+            .into_iter()
+            .map(|(fingerprint, _)| fingerprint)
+            .collect();
+        fingerprints.sort();
+        assert_eq!(fingerprints, vec!["fingerprint1", "fingerprint2"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_frecency_favors_frequently_used() -> anyhow::Result<()> {
+        // A very short half-life so that a single intervening use makes a real difference
+        // to the frecency score within this test's timescale.
+        let tempdir = TempDir::new("venvcache-journal-test")?;
+        let path = tempdir.path().join("journal.db");
+        let journal = Journal::with_half_life(path, 1, 1.0)?;
+
+        // `hot` is used constantly, `stale` is used once and left alone.
+        journal.record_usage("stale")?;
+        for _ in 0..10 {
+            journal.record_usage("hot")?;
+        }
+
+        // One more touch of `hot` should evict `stale`, not `hot`, even though `hot` was
+        // also the most recently touched fingerprint just before this call.
+        let expired_resources = journal.record_usage("hot")?;
+        assert_eq!(expired_resources, vec!["stale"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_migrate_adds_use_count_to_old_schema() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("venvcache-journal-test")?;
+        let path = tempdir.path().join("journal.db");
+
+        // Simulate a journal created before `use_count` existed.
+        {
+            let db = rusqlite::Connection::open(&path)?;
+            db.execute(
+                r#"
+                CREATE TABLE resources (
+                    fingerprint VARCHAR PRIMARY KEY,
+                    last_used DATETIME NOT NULL
+                )
+                "#,
+                (),
+            )?;
+            db.execute(
+                "INSERT INTO resources(fingerprint, last_used) VALUES (?, ?)",
+                ("fingerprint1", chrono::Utc::now()),
+            )?;
+        }
+
+        let journal = Journal::new(&path, 10)?;
+        let expired_resources = journal.record_usage("fingerprint2")?;
+        assert_eq!(expired_resources, Vec::<String>::new());
+
+        Ok(())
+    }
 }
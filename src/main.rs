@@ -1,11 +1,17 @@
 use std::io::Read;
+use std::path::Path;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
 use crate::journal::Journal;
+use crate::prune::PruneOpt;
+use crate::resolution::ResolutionCache;
 
+mod cgroup;
 mod file_lock;
 mod journal;
+mod prune;
+mod resolution;
 mod venv;
 
 #[derive(StructOpt)]
@@ -34,12 +40,46 @@ struct Opt {
     #[structopt(long)]
     requirements_path: Option<PathBuf>,
 
+    /// Caps the spawned Python process's resident memory, in bytes, via a cgroup v2
+    /// `memory.max` controller. Requires cgroup v2 delegation; a no-op otherwise.
+    #[structopt(long)]
+    memory_limit: Option<u64>,
+
+    /// Caps the spawned Python process's CPU usage, in fractional cores (e.g. `0.5`), via a
+    /// cgroup v2 `cpu.max` controller. Requires cgroup v2 delegation; a no-op otherwise.
+    #[structopt(long)]
+    cpu_limit: Option<f64>,
+
+    /// Caps the number of PIDs the spawned Python process (and its children) may create, via
+    /// a cgroup v2 `pids.max` controller. Requires cgroup v2 delegation; a no-op otherwise.
+    #[structopt(long)]
+    pids_limit: Option<u64>,
+
+    #[structopt(subcommand)]
+    subcommand: Option<SubCommand>,
+
     /// The arguments that will be passed to the Python executable inside of the virtual environment.
     #[structopt()]
     args: Vec<String>,
 }
 
+#[derive(StructOpt)]
+enum SubCommand {
+    /// Reconciles the venv directories under `--root` with the journal: deletes on-disk venvs
+    /// with no journal entry, and removes journal rows whose venv directory is gone.
+    #[structopt(alias = "gc")]
+    Prune(PruneOpt),
+}
+
 impl Opt {
+    fn resource_limits(&self) -> cgroup::ResourceLimits {
+        cgroup::ResourceLimits {
+            memory_limit: self.memory_limit,
+            cpu_limit: self.cpu_limit,
+            pids_limit: self.pids_limit,
+        }
+    }
+
     fn requirements_source(&self) -> anyhow::Result<RequirementsSource> {
         let source = match (&self.requirements, &self.requirements_path) {
             (Some(_), Some(_)) => anyhow::bail!(""),
@@ -59,20 +99,50 @@ fn main() -> anyhow::Result<()> {
     let opt = Opt::from_args();
     std::fs::create_dir_all(&opt.root)?;
 
+    if let Some(SubCommand::Prune(prune_opt)) = &opt.subcommand {
+        return prune::run(&opt.root, &opt.journal, prune_opt);
+    }
+
     let requirements = opt.requirements_source()?.read_requirements()?;
 
-    let venv_sha = venv::venv_sha(&opt.python, &requirements)?;
+    let loose_sha = venv::venv_sha(&opt.python, &requirements)?;
+    let resolution_cache = ResolutionCache::new(opt.root.join("resolutions.db"))?;
+    let venv_sha = match resolution_cache.lookup(&loose_sha)? {
+        Some(resolved_sha) => resolved_sha,
+        None => loose_sha.clone(),
+    };
+
     let venv_dir = opt.root.join(&venv_sha);
-    let mut manager = venv::VenvManager::new(venv_dir)?;
+    let mut manager = venv::VenvManager::new(venv_dir.clone())?;
 
-    let status = manager.run(&opt.python, &requirements, &opt.args)?;
+    let status = manager.run(
+        &opt.python,
+        &requirements,
+        &opt.args,
+        &opt.resource_limits(),
+    )?;
     let Some(status_code) = status.code() else {
         log::error!("Failed to create venv + run Python: {:?}", status);
         std::process::exit(127);
     };
 
+    let tracked_fingerprint = match reconcile_resolution(
+        &opt.root,
+        &loose_sha,
+        &venv_sha,
+        &venv_dir,
+        &resolution_cache,
+        &mut manager,
+    ) {
+        Ok(fingerprint) => fingerprint,
+        Err(err) => {
+            log::warn!("Failed to reconcile resolved-lock cache: {:?}", err);
+            venv_sha
+        }
+    };
+
     let journal = Journal::new(&opt.journal, opt.maximum_venvs)?;
-    let expired_venvs = journal.record_usage(&venv_sha)?;
+    let expired_venvs = journal.record_usage(&tracked_fingerprint)?;
     for expired_venv in expired_venvs {
         let expired_venv_dir = opt.root.join(&expired_venv);
         let mut expired_manager = venv::VenvManager::new(expired_venv_dir)?;
@@ -83,6 +153,77 @@ fn main() -> anyhow::Result<()> {
     std::process::exit(status_code)
 }
 
+/// After a run against `venv_dir` (currently keyed by `venv_sha`), records the fully-resolved
+/// lock it produced (if this is the first time we've seen it) so future runs with the same
+/// loose requirements can skip resolution. If the resolved lock matches a venv already built
+/// under a different loose key, `venv_dir` is replaced with a symlink to that canonical
+/// directory instead of keeping a duplicate build around; otherwise `venv_dir` is moved to the
+/// canonical directory and left behind as a symlink to it.
+///
+/// Returns the fingerprint that should be journaled for this run: `venv_sha` itself if the venv
+/// was already reconciled or `venv_sha` already equals the resolved fingerprint, otherwise the
+/// canonical resolved fingerprint the venv now lives under. Callers must journal usage under the returned fingerprint, not `venv_sha`, so a venv
+/// that gets deduped into a shared lock doesn't fragment its usage history across fingerprints.
+///
+/// Takes `manager`'s write lock around the rename/remove/symlink sequence below, and (when
+/// merging into a different venv's canonical directory) that venv's write lock too, so this
+/// stays safe to run alongside other venvcache processes reconciling the same fingerprints.
+fn reconcile_resolution(
+    root: &Path,
+    loose_sha: &str,
+    venv_sha: &str,
+    venv_dir: &Path,
+    resolution_cache: &ResolutionCache,
+    manager: &mut venv::VenvManager,
+) -> anyhow::Result<String> {
+    let resolved_lock_path = venv_dir.with_extension("resolved-lock");
+    if resolved_lock_path.exists() {
+        return Ok(venv_sha.to_owned());
+    }
+
+    let lock = venv::resolve_lock(venv_dir)?;
+    let resolved_sha = venv::lock_sha(&lock);
+
+    let _write_lock = manager.write_lock()?;
+    // Re-check now that we hold the write lock: another process may have reconciled this exact
+    // venv while we were off running `pip freeze` above.
+    if resolved_lock_path.exists() {
+        return Ok(venv_sha.to_owned());
+    }
+    std::fs::write(&resolved_lock_path, &lock)?;
+    resolution_cache.record(loose_sha, &resolved_sha)?;
+
+    if resolved_sha == venv_sha {
+        return Ok(venv_sha.to_owned());
+    }
+
+    let canonical_dir = root.join(&resolved_sha);
+    let canonical_lock_path = canonical_dir.with_extension("resolved-lock");
+    // The canonical directory may belong to a venv built under a different loose fingerprint
+    // (and thus a different lock file), so serialize against it too: two processes resolving
+    // different loose requirements to the same lock for the first time must not both try to
+    // become the canonical copy.
+    let mut canonical_manager = venv::VenvManager::new(canonical_dir.clone())?;
+    let _canonical_write_lock = canonical_manager.write_lock()?;
+
+    if canonical_dir.exists() {
+        log::debug!(
+            "Resolved lock for {:?} matches existing venv at {:?}; dropping duplicate build",
+            venv_dir,
+            canonical_dir
+        );
+        std::fs::remove_file(&resolved_lock_path)?;
+        std::fs::remove_dir_all(venv_dir)?;
+    } else {
+        std::fs::rename(venv_dir, &canonical_dir)?;
+        std::fs::rename(&resolved_lock_path, &canonical_lock_path)?;
+    }
+    std::os::unix::fs::symlink(&canonical_dir, venv_dir)?;
+    std::os::unix::fs::symlink(&canonical_lock_path, &resolved_lock_path)?;
+
+    Ok(resolved_sha)
+}
+
 enum RequirementsSource {
     Stdin,
     Provided(String),
@@ -129,4 +270,74 @@ mod tests {
         assert_eq!(requirements, "requests==4.5.6\n");
         Ok(())
     }
+
+    /// Sets up a fake venv directory at `path` whose `bin/pip freeze` just echoes `frozen`, so
+    /// `reconcile_resolution` can run `venv::resolve_lock` against it without a real Python
+    /// environment.
+    fn make_fake_venv(path: &Path, frozen: &str) -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::create_dir_all(path.join("bin"))?;
+        let pip_path = path.join("bin").join("pip");
+        std::fs::write(&pip_path, format!("#!/bin/sh\ncat <<'EOF'\n{frozen}EOF\n"))?;
+        let mut permissions = std::fs::metadata(&pip_path)?.permissions();
+        permissions.set_mode(0o755);
+        std::fs::set_permissions(&pip_path, permissions)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconcile_resolution_dedups_matching_lock_into_canonical_dir() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("venvcache-reconcile-test")?;
+        let root = tempdir.path();
+        let resolution_cache = ResolutionCache::new(root.join("resolutions.db"))?;
+
+        // Two differently-ordered (but otherwise equivalent) `pip freeze` outputs, so the two
+        // builds below normalize to the same lock despite coming from distinct loose requirements.
+        let venv_sha_a = "loose-a";
+        let venv_dir_a = root.join(venv_sha_a);
+        make_fake_venv(&venv_dir_a, "requests==2.31.0\ncertifi==2023.7.22\n")?;
+        let mut manager_a = venv::VenvManager::new(venv_dir_a.clone())?;
+        let fingerprint_a = reconcile_resolution(
+            root,
+            venv_sha_a,
+            venv_sha_a,
+            &venv_dir_a,
+            &resolution_cache,
+            &mut manager_a,
+        )?;
+
+        // The first build to resolve a given lock becomes its canonical copy: its own loose
+        // path is left behind as a symlink alias, and the fingerprint to journal is the
+        // resolved lock's hash, not the loose one it was built under.
+        assert_ne!(fingerprint_a, venv_sha_a);
+        assert!(std::fs::symlink_metadata(&venv_dir_a)?.file_type().is_symlink());
+        let canonical_dir = root.join(&fingerprint_a);
+        assert!(canonical_dir.is_dir());
+        assert_eq!(
+            resolution_cache.lookup(venv_sha_a)?,
+            Some(fingerprint_a.clone())
+        );
+
+        let venv_sha_b = "loose-b";
+        let venv_dir_b = root.join(venv_sha_b);
+        make_fake_venv(&venv_dir_b, "certifi==2023.7.22\nrequests==2.31.0\n")?;
+        let mut manager_b = venv::VenvManager::new(venv_dir_b.clone())?;
+        let fingerprint_b = reconcile_resolution(
+            root,
+            venv_sha_b,
+            venv_sha_b,
+            &venv_dir_b,
+            &resolution_cache,
+            &mut manager_b,
+        )?;
+
+        // A second, textually-different loose input that resolves to the same lock is deduped
+        // onto the existing canonical directory rather than kept as its own build.
+        assert_eq!(fingerprint_b, fingerprint_a);
+        assert!(std::fs::symlink_metadata(&venv_dir_b)?.file_type().is_symlink());
+        assert_eq!(std::fs::read_link(&venv_dir_b)?, canonical_dir);
+
+        Ok(())
+    }
 }
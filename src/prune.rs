@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+use crate::journal::Journal;
+use crate::venv::VenvManager;
+
+#[derive(StructOpt)]
+pub struct PruneOpt {
+    /// Print the reconciliation plan without deleting or modifying anything.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Only reconcile venvs idle for at least this many seconds, leaving recently-touched ones
+    /// alone even if they're otherwise orphaned or dangling.
+    #[structopt(long)]
+    max_age: Option<u64>,
+}
+
+/// Walks `root`, cross-references each venv directory's fingerprint against the journal at
+/// `journal_path`, and reconciles the two: directories with no journal entry are deleted, and
+/// journal rows whose directory is gone are removed. Each directory's write lock is taken
+/// before it's removed, so this stays safe to run alongside other venvcache processes.
+///
+/// The resolved-lock dedup layer (see `reconcile_resolution` in `main.rs`) aliases a loose
+/// requirements fingerprint onto its canonical directory via a symlink; those aliases are never
+/// journaled on their own, so a *live* one is left alone here rather than treated as orphaned.
+/// A *broken* alias (target removed out from under it) is always garbage and is swept
+/// regardless of journal state.
+pub fn run(root: &Path, journal_path: &Path, opt: &PruneOpt) -> anyhow::Result<()> {
+    let journal = Journal::new(journal_path, usize::MAX)?;
+    let journaled = journal.list_resources()?;
+    let journaled_fingerprints: HashSet<String> = journaled
+        .iter()
+        .map(|(fingerprint, _)| fingerprint.clone())
+        .collect();
+
+    let entries = venv_entries(root)?;
+    let on_disk: HashSet<&str> = entries
+        .iter()
+        .filter(|entry| entry.kind == EntryKind::Directory)
+        .map(|entry| entry.fingerprint.as_str())
+        .collect();
+
+    for entry in &entries {
+        match entry.kind {
+            EntryKind::LiveAlias => continue,
+            EntryKind::Directory if journaled_fingerprints.contains(&entry.fingerprint) => {
+                continue
+            }
+            EntryKind::Directory | EntryKind::BrokenAlias => {}
+        }
+        if let Some(max_age) = opt.max_age {
+            if !is_idle_past(&entry.path, max_age)? {
+                continue;
+            }
+        }
+
+        match entry.kind {
+            EntryKind::BrokenAlias => {
+                log::info!("broken venv alias (dangling symlink): {}", entry.fingerprint);
+            }
+            _ => {
+                log::info!(
+                    "orphaned venv directory (no journal entry): {}",
+                    entry.fingerprint
+                );
+            }
+        }
+        if opt.dry_run {
+            println!("would delete orphaned venv: {}", entry.fingerprint);
+            continue;
+        }
+        match entry.kind {
+            EntryKind::BrokenAlias => std::fs::remove_file(&entry.path)?,
+            _ => VenvManager::new(entry.path.clone())?.delete()?,
+        }
+    }
+
+    for (fingerprint, last_used) in &journaled {
+        if on_disk.contains(fingerprint.as_str()) {
+            continue;
+        }
+        if let Some(max_age) = opt.max_age {
+            let age = chrono::Utc::now().signed_duration_since(*last_used);
+            if age.num_seconds() < max_age as i64 {
+                continue;
+            }
+        }
+
+        log::info!("dangling journal entry (no venv directory): {}", fingerprint);
+        if opt.dry_run {
+            println!("would remove dangling journal entry: {fingerprint}");
+            continue;
+        }
+        journal.mark_deleted(fingerprint)?;
+    }
+
+    Ok(())
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum EntryKind {
+    /// A real venv directory.
+    Directory,
+    /// A resolved-lock dedup alias symlink whose target still exists.
+    LiveAlias,
+    /// A resolved-lock dedup alias symlink whose target is gone.
+    BrokenAlias,
+}
+
+/// A top-level entry under `root`, treated as a venv fingerprint.
+struct VenvEntry {
+    fingerprint: String,
+    path: PathBuf,
+    kind: EntryKind,
+}
+
+/// Lists the top-level entries directly under `root` that represent venv fingerprints: real
+/// venv directories, and both live and broken dedup-alias symlinks. `DirEntry::file_type`
+/// doesn't follow symlinks, so a live alias is distinguished from a real directory by stat'ing
+/// through it with `Path::is_dir`.
+fn venv_entries(root: &Path) -> anyhow::Result<Vec<VenvEntry>> {
+    let mut entries = Vec::new();
+    for dir_entry in std::fs::read_dir(root)? {
+        let dir_entry = dir_entry?;
+        let file_type = dir_entry.file_type()?;
+        let path = dir_entry.path();
+
+        let kind = if file_type.is_dir() {
+            EntryKind::Directory
+        } else if file_type.is_symlink() && path.is_dir() {
+            EntryKind::LiveAlias
+        } else if file_type.is_symlink() && !path.exists() {
+            EntryKind::BrokenAlias
+        } else {
+            continue;
+        };
+
+        entries.push(VenvEntry {
+            fingerprint: dir_entry.file_name().to_string_lossy().into_owned(),
+            path,
+            kind,
+        });
+    }
+    Ok(entries)
+}
+
+fn is_idle_past(path: &Path, max_age_secs: u64) -> anyhow::Result<bool> {
+    let modified = std::fs::symlink_metadata(path)?.modified()?;
+    match modified.elapsed() {
+        Ok(elapsed) => Ok(elapsed >= Duration::from_secs(max_age_secs)),
+        Err(_) => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn prune_opt(dry_run: bool, max_age: Option<u64>) -> PruneOpt {
+        PruneOpt { dry_run, max_age }
+    }
+
+    #[test]
+    fn test_prune_deletes_orphaned_venv_directory() -> anyhow::Result<()> {
+        let root = TempDir::new("venvcache-prune-test")?;
+        let journal_path = root.path().join("journal.db");
+        std::fs::create_dir_all(root.path().join("orphan"))?;
+
+        run(root.path(), &journal_path, &prune_opt(false, None))?;
+
+        assert!(!root.path().join("orphan").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_dry_run_does_not_delete() -> anyhow::Result<()> {
+        let root = TempDir::new("venvcache-prune-test")?;
+        let journal_path = root.path().join("journal.db");
+        std::fs::create_dir_all(root.path().join("orphan"))?;
+
+        run(root.path(), &journal_path, &prune_opt(true, None))?;
+
+        assert!(root.path().join("orphan").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_keeps_journaled_venv_directory() -> anyhow::Result<()> {
+        let root = TempDir::new("venvcache-prune-test")?;
+        let journal_path = root.path().join("journal.db");
+        std::fs::create_dir_all(root.path().join("kept"))?;
+        Journal::new(&journal_path, 10)?.record_usage("kept")?;
+
+        run(root.path(), &journal_path, &prune_opt(false, None))?;
+
+        assert!(root.path().join("kept").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_removes_dangling_journal_entry() -> anyhow::Result<()> {
+        let root = TempDir::new("venvcache-prune-test")?;
+        let journal_path = root.path().join("journal.db");
+        Journal::new(&journal_path, 10)?.record_usage("missing")?;
+
+        run(root.path(), &journal_path, &prune_opt(false, None))?;
+
+        let journal = Journal::new(&journal_path, 10)?;
+        assert_eq!(
+            journal.list_resources()?,
+            Vec::<(String, chrono::DateTime<chrono::Utc>)>::new()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_keeps_live_alias_symlink_unjournaled() -> anyhow::Result<()> {
+        let root = TempDir::new("venvcache-prune-test")?;
+        let journal_path = root.path().join("journal.db");
+
+        std::fs::create_dir_all(root.path().join("canonical"))?;
+        std::os::unix::fs::symlink(root.path().join("canonical"), root.path().join("alias"))?;
+        Journal::new(&journal_path, 10)?.record_usage("canonical")?;
+
+        run(root.path(), &journal_path, &prune_opt(false, None))?;
+
+        assert!(root.path().join("canonical").exists());
+        assert!(root.path().join("alias").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_deletes_broken_alias_symlink() -> anyhow::Result<()> {
+        let root = TempDir::new("venvcache-prune-test")?;
+        let journal_path = root.path().join("journal.db");
+
+        std::os::unix::fs::symlink(root.path().join("missing-target"), root.path().join("alias"))?;
+
+        run(root.path(), &journal_path, &prune_opt(false, None))?;
+
+        assert!(std::fs::symlink_metadata(root.path().join("alias")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_respects_max_age() -> anyhow::Result<()> {
+        let root = TempDir::new("venvcache-prune-test")?;
+        let journal_path = root.path().join("journal.db");
+        std::fs::create_dir_all(root.path().join("orphan"))?;
+
+        run(root.path(), &journal_path, &prune_opt(false, Some(3600)))?;
+
+        assert!(root.path().join("orphan").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+
+/// Maps the hash of a venv's loose (as-given) requirements and Python version to the hash of
+/// the fully-resolved, pinned lock that requirements resolved to, so a subsequent run with
+/// already-seen loose requirements can skip resolution entirely and reuse the venv keyed by
+/// the resolved lock.
+pub struct ResolutionCache {
+    db: rusqlite::Connection,
+}
+
+impl ResolutionCache {
+    pub fn new(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut cache = Self {
+            db: rusqlite::Connection::open(path)?,
+        };
+        cache.migrate()?;
+        Ok(cache)
+    }
+
+    fn migrate(&mut self) -> anyhow::Result<()> {
+        self.db.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS resolutions (
+                loose_sha VARCHAR PRIMARY KEY,
+                resolved_sha VARCHAR NOT NULL
+            )
+            "#,
+            (),
+        )?;
+        Ok(())
+    }
+
+    /// Looks up the resolved lock hash previously recorded for `loose_sha`, if any.
+    pub fn lookup(&self, loose_sha: &str) -> anyhow::Result<Option<String>> {
+        let resolved_sha = self.db.query_row(
+            "SELECT resolved_sha FROM resolutions WHERE loose_sha = ?",
+            (loose_sha,),
+            |row| row.get(0),
+        );
+        match resolved_sha {
+            Ok(resolved_sha) => Ok(Some(resolved_sha)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Records that `loose_sha` resolves to `resolved_sha`.
+    pub fn record(&self, loose_sha: &str, resolved_sha: &str) -> anyhow::Result<()> {
+        self.db.execute(
+            r#"
+            INSERT INTO resolutions(loose_sha, resolved_sha)
+            VALUES (?, ?)
+            ON CONFLICT(loose_sha)
+            DO UPDATE SET resolved_sha=?
+            "#,
+            (loose_sha, resolved_sha, resolved_sha),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn test_cache() -> anyhow::Result<(TempDir, ResolutionCache)> {
+        let tempdir = TempDir::new("venvcache-resolution-test")?;
+        let path = tempdir.path().join("resolutions.db");
+        Ok((tempdir, ResolutionCache::new(path)?))
+    }
+
+    #[test]
+    fn test_resolution_cache_miss() -> anyhow::Result<()> {
+        let (_tempdir, cache) = test_cache()?;
+        assert_eq!(cache.lookup("loose")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolution_cache_record_and_lookup() -> anyhow::Result<()> {
+        let (_tempdir, cache) = test_cache()?;
+        cache.record("loose", "resolved")?;
+        assert_eq!(cache.lookup("loose")?, Some("resolved".to_owned()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolution_cache_record_overwrites() -> anyhow::Result<()> {
+        let (_tempdir, cache) = test_cache()?;
+        cache.record("loose", "resolved1")?;
+        cache.record("loose", "resolved2")?;
+        assert_eq!(cache.lookup("loose")?, Some("resolved2".to_owned()));
+        Ok(())
+    }
+}
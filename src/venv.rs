@@ -1,11 +1,20 @@
+use crate::cgroup;
+use crate::cgroup::ResourceLimits;
 use crate::file_lock::FileLock;
+use crate::file_lock::LockError;
 use crate::file_lock::ReadLock;
+use crate::file_lock::WriteLock;
 use std::fs::File;
 use std::io::Write;
+use std::os::unix::process::CommandExt;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::process::ExitStatus;
+use std::time::Duration;
+
+/// How long to wait on another process's build before re-logging that we're still waiting.
+const LOCK_WAIT_LOG_INTERVAL: Duration = Duration::from_secs(30);
 
 pub struct VenvManager {
     path: PathBuf,
@@ -26,16 +35,63 @@ impl VenvManager {
         python_executable: &Path,
         requirements: &str,
         args: &[String],
+        resource_limits: &ResourceLimits,
     ) -> anyhow::Result<ExitStatus> {
         log::debug!("Running Python in virtual environment at {:?}", self.path);
-        let mut _read_lock = self.lock.read()?;
+        let mut _read_lock = match self.lock.try_read() {
+            Ok(read_lock) => read_lock,
+            Err(LockError::WouldBlock) => {
+                log::info!("waiting for another process to build this venv...");
+                loop {
+                    match self.lock.read_timeout(LOCK_WAIT_LOG_INTERVAL) {
+                        Ok(read_lock) => break read_lock,
+                        Err(LockError::WouldBlock) => {
+                            log::info!("still waiting for another process to build this venv...");
+                            continue;
+                        }
+                        Err(LockError::Other(err)) => return Err(err),
+                    }
+                }
+            }
+            Err(LockError::Other(err)) => return Err(err),
+        };
 
         let venv_python = self.path.join("bin").join("python");
         if !venv_python.exists() {
             _read_lock = create_venv(_read_lock, python_executable, requirements, &self.path)?;
         }
 
-        let status = match Command::new(venv_python).args(args).status() {
+        // The cgroup is created (and, below, joined) before the child is spawned, so the
+        // process is already confined by the time it execs into the Python interpreter; there's
+        // no window where it runs unthrottled.
+        let cgroup_name = format!("venvcache-{}", std::process::id());
+        let cgroup_guard = match cgroup::create(&cgroup_name, resource_limits) {
+            Ok(guard) => guard,
+            Err(err) => {
+                log::warn!("Failed to apply cgroup resource limits: {:?}", err);
+                None
+            }
+        };
+
+        let mut command = Command::new(venv_python);
+        command.args(args);
+        if let Some(guard) = &cgroup_guard {
+            let procs_path = guard.procs_path();
+            // Safety: this closure runs in the forked child between `fork` and `execve`, before
+            // the child is spawned, so it joins its own (not-yet-exec'd) pid to the cgroup
+            // rather than racing a post-spawn write from the parent.
+            unsafe {
+                command.pre_exec(move || std::fs::write(&procs_path, std::process::id().to_string()));
+            }
+        }
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                anyhow::bail!("Failed to spawn Python: {:?}", err);
+            }
+        };
+
+        let status = match child.wait() {
             Ok(status) => status,
             Err(err) => {
                 anyhow::bail!("Failed to get status from Python: {:?}", err);
@@ -50,6 +106,12 @@ impl VenvManager {
         std::fs::remove_dir_all(&self.path)?;
         Ok(())
     }
+
+    /// Takes this venv's write lock, for callers outside this module (the resolved-lock dedup
+    /// reconciliation in `main.rs`) that need to mutate the venv directory directly.
+    pub fn write_lock(&mut self) -> anyhow::Result<WriteLock> {
+        self.lock.write()
+    }
 }
 
 pub fn venv_sha(python_executable: &Path, requirements: &str) -> anyhow::Result<String> {
@@ -59,6 +121,36 @@ pub fn venv_sha(python_executable: &Path, requirements: &str) -> anyhow::Result<
     )))
 }
 
+/// Runs `pip freeze` inside the venv at `venv_path` and returns the canonical, fully-pinned
+/// lock: one `package==version` per line, trimmed, with blank lines dropped, sorted so that
+/// differently-ordered or differently-formatted (but equivalent) requirements resolve to the
+/// same lock text.
+pub fn resolve_lock(venv_path: &Path) -> anyhow::Result<String> {
+    let venv_pip = venv_path.join("bin").join("pip");
+    let output = Command::new(venv_pip).arg("freeze").output()?;
+    anyhow::ensure!(output.status.success(), "Failed to run pip freeze");
+    let frozen = String::from_utf8(output.stdout)?;
+    Ok(normalize_lock(&frozen))
+}
+
+fn normalize_lock(frozen: &str) -> String {
+    let mut lines: Vec<&str> = frozen
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.sort_unstable();
+    let mut normalized = lines.join("\n");
+    normalized.push('\n');
+    normalized
+}
+
+/// Hashes a resolved lock (as produced by [`resolve_lock`]) so it can be used as a
+/// content-addressed venv key.
+pub fn lock_sha(lock: &str) -> String {
+    sha256::digest(lock)
+}
+
 fn create_venv<'a>(
     read_lock: ReadLock<'a>,
     python_executable: &Path,
@@ -104,3 +196,26 @@ fn python_version(python_executable: &Path) -> anyhow::Result<String> {
     let output = Command::new(python_executable).arg("--version").output()?;
     Ok(String::from_utf8(output.stdout)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_lock_sorts_and_trims() {
+        let frozen = "requests==2.31.0\n  \nurllib3==2.0.7  \ncertifi==2023.7.22\n";
+        let normalized = normalize_lock(frozen);
+        assert_eq!(
+            normalized,
+            "certifi==2023.7.22\nrequests==2.31.0\nurllib3==2.0.7\n"
+        );
+    }
+
+    #[test]
+    fn test_normalize_lock_ignores_ordering() {
+        let a = normalize_lock("requests==2.31.0\nurllib3==2.0.7\n");
+        let b = normalize_lock("urllib3==2.0.7\nrequests==2.31.0\n");
+        assert_eq!(a, b);
+        assert_eq!(lock_sha(&a), lock_sha(&b));
+    }
+}